@@ -0,0 +1,131 @@
+//! pluggable connection transport
+//!
+//! the server loop only needs something it can read/write bytes through;
+//! this module pulls that need out into an [`IoStream`] trait plus an
+//! [`Acceptor`] that turns a freshly-accepted `TcpStream` into one, so the
+//! coroutine-per-connection loop in `http_server` stays transport-agnostic.
+//! `DefaultAcceptor` is the plain-tcp passthrough; the `tls` feature adds a
+//! `rustls`-backed acceptor for HTTPS.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use may::net::TcpStream;
+
+/// anything the server loop can read a request from and write a response to
+pub trait IoStream: Read + Write + Send {
+    /// arm (or disarm) the read timeout on the underlying socket
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+
+    /// duplicate this stream so the pipeline server can read and write it
+    /// from separate coroutines; not every transport can support this
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized;
+}
+
+impl IoStream for TcpStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, dur)
+    }
+
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// turns a raw, just-accepted `TcpStream` into the `IoStream` the server
+/// loop will actually speak the http protocol over
+pub trait Acceptor: Send + Sync + 'static {
+    type Io: IoStream + 'static;
+
+    fn accept(&self, raw: TcpStream) -> io::Result<Self::Io>;
+}
+
+/// the plain-tcp acceptor used by default: no wrapping at all
+#[derive(Clone, Copy, Default)]
+pub struct DefaultAcceptor;
+
+impl Acceptor for DefaultAcceptor {
+    type Io = TcpStream;
+
+    fn accept(&self, raw: TcpStream) -> io::Result<TcpStream> {
+        Ok(raw)
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use self::tls::TlsAcceptor;
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::io::{self, Read, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use may::net::TcpStream;
+    use rustls::{ServerConfig as TlsServerConfig, ServerSession, StreamOwned};
+
+    use super::{Acceptor, IoStream};
+
+    /// a TLS-wrapped connection; the handshake runs on its first read or
+    /// write, so the rest of the coroutine-per-connection loop sees a plain
+    /// `Read + Write`
+    pub struct TlsStream(StreamOwned<ServerSession, TcpStream>);
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for TlsStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl IoStream for TlsStream {
+        fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            self.0.get_ref().set_read_timeout(dur)
+        }
+
+        fn try_clone(&self) -> io::Result<TlsStream> {
+            // a TLS session cannot be safely duplicated; the pipeline
+            // server needs independent read/write handles, so TLS only
+            // supports `HttpServer`, not `HttpPipelineServer`, for now
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "TlsStream does not support try_clone (no pipeline server support yet)",
+            ))
+        }
+    }
+
+    /// a `rustls`-backed acceptor: wraps each accepted `TcpStream` in a TLS
+    /// server session using a shared certificate/key configuration
+    #[derive(Clone)]
+    pub struct TlsAcceptor {
+        config: Arc<TlsServerConfig>,
+    }
+
+    impl TlsAcceptor {
+        pub fn new(config: TlsServerConfig) -> TlsAcceptor {
+            TlsAcceptor {
+                config: Arc::new(config),
+            }
+        }
+    }
+
+    impl Acceptor for TlsAcceptor {
+        type Io = TlsStream;
+
+        fn accept(&self, raw: TcpStream) -> io::Result<TlsStream> {
+            let session = ServerSession::new(&self.config);
+            Ok(TlsStream(StreamOwned::new(session, raw)))
+        }
+    }
+}
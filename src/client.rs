@@ -0,0 +1,126 @@
+//! a minimal coroutine-based http/1.1 client, built on the same
+//! `request`/`response` codecs the server uses
+//!
+//! ```ignore
+//! let mut client = HttpClient::connect("127.0.0.1:8080")?;
+//! let rsp = client.get("/").header("Host", "127.0.0.1").send()?;
+//! ```
+
+use std::io::{self, Read, Write};
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use bytes::{BufMut, BytesMut};
+use may::net::TcpStream;
+
+use request;
+use response::{self, DecodedResponse};
+
+/// a connection to a http server
+///
+/// the underlying socket is kept open across calls to `send` so a `keep
+/// -alive` connection can be reused for multiple requests, mirroring the
+/// server's own keep-alive handling
+pub struct HttpClient {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl HttpClient {
+    /// connect to `addr`
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<HttpClient> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(HttpClient {
+            stream,
+            buf: BytesMut::with_capacity(4096),
+        })
+    }
+
+    /// cap how long `send` will wait for the server to respond
+    pub fn read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(dur)
+    }
+
+    pub fn get(&mut self, path: &str) -> RequestBuilder {
+        RequestBuilder::new(self, "GET", path)
+    }
+
+    pub fn post(&mut self, path: &str) -> RequestBuilder {
+        RequestBuilder::new(self, "POST", path)
+    }
+
+    /// build a request with an arbitrary method
+    pub fn request(&mut self, method: &str, path: &str) -> RequestBuilder {
+        RequestBuilder::new(self, method, path)
+    }
+
+    fn send(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> io::Result<DecodedResponse> {
+        let mut out = BytesMut::with_capacity(256 + body.len());
+        request::encode(method, path, headers, body, &mut out);
+        self.stream.write_all(out.as_ref())?;
+
+        loop {
+            if let Some(rsp) = response::decode(&mut self.buf)? {
+                return Ok(rsp);
+            }
+            if self.buf.remaining_mut() < 1024 {
+                self.buf.reserve(4096);
+            }
+            let n = {
+                let read_buf = unsafe { self.buf.bytes_mut() };
+                self.stream.read(read_buf)?
+            };
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed before a full response was read",
+                ));
+            }
+            unsafe { self.buf.advance_mut(n) };
+        }
+    }
+}
+
+/// a request under construction; add headers/a body, then `send()` it
+pub struct RequestBuilder<'a> {
+    client: &'a mut HttpClient,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl<'a> RequestBuilder<'a> {
+    fn new(client: &'a mut HttpClient, method: &str, path: &str) -> RequestBuilder<'a> {
+        RequestBuilder {
+            client,
+            method: method.to_owned(),
+            path: path.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    /// write the request and block this coroutine until the full response
+    /// has been read back
+    pub fn send(self) -> io::Result<DecodedResponse> {
+        self.client
+            .send(&self.method, &self.path, &self.headers, &self.body)
+    }
+}
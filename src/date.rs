@@ -0,0 +1,24 @@
+//! a cached http date string, kept per thread and reformatted by `write`
+//! whenever the current second differs from the cached one
+
+use std::cell::RefCell;
+use std::fmt::Write;
+
+use time;
+
+thread_local!(static CACHED: RefCell<(String, time::Timespec)> = RefCell::new((String::new(), time::Timespec::new(0, 0))));
+
+/// write the current http date into `dst`, using a per-thread cache so
+/// we don't reformat the same second's timestamp on every request
+pub fn write(dst: &mut String) {
+    CACHED.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let now = time::get_time();
+        if now.sec != cache.1.sec {
+            cache.0.clear();
+            let _ = write!(cache.0, "{}", time::at_utc(now).rfc822());
+            cache.1 = now;
+        }
+        dst.push_str(&cache.0);
+    });
+}
@@ -0,0 +1,48 @@
+//! classifying failures the server loop can run into, so `HttpService`
+//! implementors get one place ([`HttpService::on_error`]) to map them to
+//! safe, client-facing responses instead of a generic leaky 500
+
+use std::io;
+
+use response::Response;
+
+/// something that went wrong while dispatching a request, handed to
+/// [`HttpService::on_error`][crate::HttpService::on_error]
+pub enum DispatchError {
+    /// the request line or headers failed to parse
+    Parse(io::Error),
+    /// a connection-level read/write failure
+    Io(io::Error),
+    /// `HttpService::call` returned an error
+    Service(io::Error),
+    /// the request exceeded the server's buffer limit before it could be
+    /// fully parsed
+    PayloadTooLarge,
+    /// the client went silent mid-request past the configured read timeout
+    Timeout,
+}
+
+/// the default `on_error` mapping: a generic response per error class, with
+/// no internal error details in the body
+pub fn default_error_response(err: &DispatchError) -> Response {
+    let mut rsp = Response::new();
+    match *err {
+        DispatchError::Parse(_) => {
+            rsp.status_code(400, "Bad Request");
+            rsp.body("Bad Request");
+        }
+        DispatchError::PayloadTooLarge => {
+            rsp.status_code(413, "Payload Too Large");
+            rsp.body("Payload Too Large");
+        }
+        DispatchError::Timeout => {
+            rsp.status_code(408, "Request Timeout");
+            rsp.body("Request Timeout");
+        }
+        DispatchError::Io(_) | DispatchError::Service(_) => {
+            rsp.status_code(500, "Internal Server Error");
+            rsp.body("Internal Server Error");
+        }
+    }
+    rsp
+}
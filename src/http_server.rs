@@ -1,22 +1,110 @@
 //! http server implementation on top of `MAY`
 
-use std::error::Error;
 use std::io::{self, Read, Write};
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
+use std::time::Duration;
 
 use sequencer::Seq;
 use may::coroutine;
 use may::net::TcpListener;
+use acceptor::{Acceptor, DefaultAcceptor, IoStream};
+use error::{self, DispatchError};
 use request::{self, Request};
 use bytes::{BufMut, BytesMut};
 use response::{self, Response};
+use websocket;
+
+/// default cap on how large a request's buffered bytes (request line,
+/// headers and body so far) may grow while still unparsed; reaching it fails
+/// the connection with `413 Payload Too Large`
+const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// tunables for how a server manages a single keep-alive connection
+///
+/// modeled after actix's worker `keep_alive`/`client_timeout` settings: a
+/// connection is dropped if the client goes silent for longer than
+/// `client_timeout` mid-request, or longer than `keep_alive` between
+/// requests, and is closed after `max_requests_per_conn` requests even if
+/// the client would happily keep it open.
+#[derive(Clone, Copy)]
+pub struct ServerConfig {
+    keep_alive: Duration,
+    client_timeout: Duration,
+    max_requests_per_conn: Option<usize>,
+    max_request_size: usize,
+}
+
+impl ServerConfig {
+    pub fn new() -> ServerConfig {
+        ServerConfig {
+            keep_alive: Duration::from_secs(5),
+            client_timeout: Duration::from_secs(30),
+            max_requests_per_conn: None,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+        }
+    }
+
+    /// how long to keep an idle connection open between requests
+    pub fn keep_alive(mut self, dur: Duration) -> Self {
+        self.keep_alive = dur;
+        self
+    }
+
+    /// how long each read may wait for more of a request's bytes; the timer
+    /// restarts on every read, so it does not bound the whole request
+    pub fn client_timeout(mut self, dur: Duration) -> Self {
+        self.client_timeout = dur;
+        self
+    }
+
+    /// close the connection after this many requests, regardless of
+    /// keep-alive; `None` means unlimited
+    pub fn max_requests_per_conn(mut self, max: Option<usize>) -> Self {
+        self.max_requests_per_conn = max;
+        self
+    }
+
+    /// fail the connection with `413 Payload Too Large` once an unparsed
+    /// request's buffered bytes - request line, headers, and any body
+    /// collected so far - reach this many bytes
+    pub fn max_request_size(mut self, max: usize) -> Self {
+        self.max_request_size = max;
+        self
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig::new()
+    }
+}
+
+/// true if the parsed request indicates the client wants the connection
+/// closed after this response
+fn wants_close(req: &Request) -> bool {
+    match req.header("Connection") {
+        Some(v) => v.eq_ignore_ascii_case("close"),
+        None => req.version() == 0, // HTTP/1.0 defaults to close
+    }
+}
 
 /// the http service trait
 /// user code should supply a type that impl the `call` method for the http server
 ///
 pub trait HttpService {
     fn call(&self, _request: Request) -> io::Result<Response>;
+
+    /// map a dispatch failure to the response sent back to the client
+    ///
+    /// the default sanitizes `err` into a generic response per error class
+    /// (no internal error details in the body); override to customize that
+    /// mapping, or return `None` to send no reply. The connection is then
+    /// closed, except after a `Service` error on `HttpPipelineServer`, which
+    /// keeps reading the requests behind it.
+    fn on_error(&self, err: DispatchError) -> Option<Response> {
+        Some(error::default_error_response(&err))
+    }
 }
 
 macro_rules! t {
@@ -44,58 +132,247 @@ macro_rules! t_c {
     })
 }
 
-fn internal_error_rsp(e: io::Error) -> Response {
-    error!("error in service: err = {:?}", e);
-    let mut err_rsp = Response::new();
-    err_rsp.status_code(500, "Internal Server Error");
-    err_rsp.body(e.description());
-    err_rsp
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::ConnectionReset || err.kind() == io::ErrorKind::UnexpectedEof
+}
+
+/// write `rsp` (the error response from `on_error`) to `stream` and
+/// swallow any write failure: the connection is being closed either way
+///
+/// `http10` picks the status line's protocol version; these errors fire
+/// before a request has been fully decoded, so callers pass their best
+/// guess (see [`request::peek_version`]), defaulting to HTTP/1.1 rather
+/// than downgrading a 1.1 client's response to a 1.0 status line
+fn write_error_rsp<S: Write>(stream: &mut S, rsp: Response, http10: bool, scratch: &mut BytesMut) {
+    scratch.clear();
+    // an overridden `on_error` may return a streamed body, which still goes
+    // out in full; an upgrade handler is dropped without being called
+    let tail = response::encode(rsp, http10, scratch);
+    let _ = stream.write_all(scratch.as_ref());
+    if let response::Tail::Chunked(body) = tail {
+        let _ = response::write_chunked(body, stream);
+    }
+}
+
+/// best-effort HTTP version to use for an error response written before a
+/// request has been fully decoded; defaults to HTTP/1.1 when the version
+/// isn't known yet rather than downgrading to HTTP/1.0
+fn guess_http10(buf: &[u8]) -> bool {
+    request::peek_version(buf) == Some(0)
 }
 
 /// this is the generic type http server
 /// with a type parameter that impl `HttpService` trait
 ///
-pub struct HttpServer<T>(pub T);
+/// `A` is the connection [`Acceptor`], defaulting to plain TCP; swap it for
+/// e.g. a `TlsAcceptor` to serve HTTPS without touching the request loop
+pub struct HttpServer<T, A = DefaultAcceptor> {
+    pub service: T,
+    pub config: ServerConfig,
+    pub acceptor: A,
+}
+
+impl<T> HttpServer<T> {
+    pub fn new(service: T) -> HttpServer<T> {
+        HttpServer {
+            service,
+            config: ServerConfig::new(),
+            acceptor: DefaultAcceptor,
+        }
+    }
+}
 
-impl<T: HttpService + Send + Sync + 'static> HttpServer<T> {
+impl<T, A> HttpServer<T, A> {
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// use a different connection acceptor, e.g. a TLS acceptor
+    pub fn acceptor<A2: Acceptor>(self, acceptor: A2) -> HttpServer<T, A2> {
+        HttpServer {
+            service: self.service,
+            config: self.config,
+            acceptor,
+        }
+    }
+}
+
+impl<T: HttpService + Send + Sync + 'static, A: Acceptor> HttpServer<T, A> {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
         let listener = TcpListener::bind(addr)?;
+        let config = self.config;
         go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
             move || {
-                let server = Arc::new(self);
-                for stream in listener.incoming() {
-                    let mut stream = t_c!(stream);
+                let server = Arc::new(self.service);
+                let acceptor = Arc::new(self.acceptor);
+                for raw in listener.incoming() {
+                    let raw = t_c!(raw);
                     let server = server.clone();
+                    let acceptor = acceptor.clone();
                     go!(move || {
+                        let mut stream = t!(acceptor.accept(raw));
                         let mut buf = BytesMut::with_capacity(512);
                         let mut rsp = BytesMut::with_capacity(512);
+                        let mut req_count: usize = 0;
+                        t!(stream.set_read_timeout(Some(config.client_timeout)));
                         loop {
-                            match t!(request::decode(&mut buf)) {
+                            let decoded = match request::decode(&mut buf) {
+                                Ok(decoded) => decoded,
+                                Err(err) => {
+                                    if let Some(err_rsp) = server.on_error(DispatchError::Parse(err)) {
+                                        write_error_rsp(
+                                            &mut stream,
+                                            err_rsp,
+                                            guess_http10(&buf),
+                                            &mut rsp,
+                                        );
+                                    }
+                                    return;
+                                }
+                            };
+                            match decoded {
                                 None => {
+                                    if buf.len() >= config.max_request_size {
+                                        if let Some(err_rsp) =
+                                            server.on_error(DispatchError::PayloadTooLarge)
+                                        {
+                                            write_error_rsp(
+                                                &mut stream,
+                                                err_rsp,
+                                                guess_http10(&buf),
+                                                &mut rsp,
+                                            );
+                                        }
+                                        return;
+                                    }
                                     // need more data
                                     if buf.remaining_mut() < 256 {
                                         buf.reserve(512);
                                     }
+                                    let was_idle = buf.is_empty();
                                     let n = {
                                         let read_buf = unsafe { buf.bytes_mut() };
-                                        t!(stream.read(read_buf))
+                                        match stream.read(read_buf) {
+                                            Ok(n) => n,
+                                            Err(ref err) if is_disconnect(err) => return,
+                                            Err(ref err) if is_timeout(err) => {
+                                                // idle between requests: nothing to
+                                                // respond to, just drop the connection
+                                                if buf.is_empty() {
+                                                    return;
+                                                }
+                                                if let Some(err_rsp) =
+                                                    server.on_error(DispatchError::Timeout)
+                                                {
+                                                    write_error_rsp(
+                                                        &mut stream,
+                                                        err_rsp,
+                                                        guess_http10(&buf),
+                                                        &mut rsp,
+                                                    );
+                                                }
+                                                return;
+                                            }
+                                            Err(err) => {
+                                                if let Some(err_rsp) =
+                                                    server.on_error(DispatchError::Io(err))
+                                                {
+                                                    write_error_rsp(
+                                                        &mut stream,
+                                                        err_rsp,
+                                                        guess_http10(&buf),
+                                                        &mut rsp,
+                                                    );
+                                                }
+                                                return;
+                                            }
+                                        }
                                     };
                                     if n == 0 {
                                         //connection was closed
                                         return;
                                     }
+                                    if was_idle {
+                                        // the first byte of a new request just arrived:
+                                        // re-arm client_timeout for the rest of this
+                                        // request's read, in place of keep_alive
+                                        t!(stream.set_read_timeout(Some(config.client_timeout)));
+                                    }
                                     unsafe { buf.advance_mut(n) };
                                 }
                                 Some(req) => {
-                                    let ret = server.0.call(req).unwrap_or_else(internal_error_rsp);
-                                    response::encode(ret, &mut rsp);
+                                    req_count += 1;
+                                    let mut close = wants_close(&req);
+                                    if let Some(max) = config.max_requests_per_conn {
+                                        close = close || req_count >= max;
+                                    }
+                                    let http10 = req.version() == 0;
+                                    let ws_accept = if req.version() == 1
+                                        && websocket::is_upgrade_request(
+                                            req.header("Connection"),
+                                            req.header("Upgrade"),
+                                        ) {
+                                        req.header("Sec-WebSocket-Key")
+                                            .map(websocket::accept_key)
+                                    } else {
+                                        None
+                                    };
+
+                                    let mut ret = match server.call(req) {
+                                        Ok(rsp) => rsp,
+                                        Err(e) => match server.on_error(DispatchError::Service(e)) {
+                                            Some(rsp) => rsp,
+                                            None => return,
+                                        },
+                                    };
+                                    if ret.is_upgrade() {
+                                        if let Some(ref key) = ws_accept {
+                                            ret.header("Sec-WebSocket-Accept", key);
+                                        }
+                                    }
+                                    if close {
+                                        ret.header("Connection", "close");
+                                    } else if http10 {
+                                        // HTTP/1.0 defaults to close, so tell a
+                                        // conforming 1.0 client it asked for (and
+                                        // got) a reusable connection
+                                        ret.header("Connection", "keep-alive");
+                                    }
+                                    let tail = response::encode(ret, http10, &mut rsp);
 
                                     // send the result back to client
                                     t!(stream.write_all(rsp.as_ref()));
                                     rsp.clear();
+                                    match tail {
+                                        response::Tail::Done => {}
+                                        response::Tail::Chunked(body) => {
+                                            t!(response::write_chunked(body, &mut stream));
+                                        }
+                                        response::Tail::Upgrade(handler) => {
+                                            // any bytes already read past this request
+                                            // (e.g. the client's first ws frame) belong
+                                            // to the upgraded stream, not the next request
+                                            let leftover = buf.split_to(buf.len()).to_vec();
+                                            handler(websocket::UpgradedStream::new(
+                                                Box::new(stream),
+                                                leftover,
+                                            ));
+                                            return;
+                                        }
+                                    }
+
+                                    if close {
+                                        return;
+                                    }
+                                    t!(stream.set_read_timeout(Some(config.keep_alive)));
                                 }
                             }
                         }
@@ -109,54 +386,209 @@ impl<T: HttpService + Send + Sync + 'static> HttpServer<T> {
 /// this is the pipeline type http server
 /// with a type parameter that impl `HttpService` trait
 ///
-pub struct HttpPipelineServer<T>(pub T);
+/// `A` is the connection [`Acceptor`], defaulting to plain TCP; note that
+/// not every acceptor's `Io` supports the `try_clone` that pipelining needs
+/// (see `TlsStream`)
+pub struct HttpPipelineServer<T, A = DefaultAcceptor> {
+    pub service: T,
+    pub config: ServerConfig,
+    pub acceptor: A,
+}
 
-impl<T: HttpService + Send + Sync + 'static> HttpPipelineServer<T> {
+impl<T> HttpPipelineServer<T> {
+    pub fn new(service: T) -> HttpPipelineServer<T> {
+        HttpPipelineServer {
+            service,
+            config: ServerConfig::new(),
+            acceptor: DefaultAcceptor,
+        }
+    }
+}
+
+impl<T, A> HttpPipelineServer<T, A> {
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// use a different connection acceptor, e.g. a TLS acceptor
+    pub fn acceptor<A2: Acceptor>(self, acceptor: A2) -> HttpPipelineServer<T, A2> {
+        HttpPipelineServer {
+            service: self.service,
+            config: self.config,
+            acceptor,
+        }
+    }
+}
+
+impl<T: HttpService + Send + Sync + 'static, A: Acceptor> HttpPipelineServer<T, A> {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
         let listener = TcpListener::bind(addr)?;
+        let config = self.config;
         go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
             move || {
-                let server = Arc::new(self);
-                for stream in listener.incoming() {
-                    let stream = t_c!(stream);
+                let server = Arc::new(self.service);
+                let acceptor = Arc::new(self.acceptor);
+                for raw in listener.incoming() {
+                    let raw = t_c!(raw);
+                    let acceptor = acceptor.clone();
+                    let stream = t_c!(acceptor.accept(raw));
                     let mut reader = t_c!(stream.try_clone());
                     let writer = Seq::new(stream);
                     let server = server.clone();
                     go!(move || {
                         let mut buf = BytesMut::with_capacity(4096);
+                        let mut scratch = BytesMut::with_capacity(256);
+                        let mut req_count: usize = 0;
+                        t!(reader.set_read_timeout(Some(config.client_timeout)));
                         loop {
-                            match t!(request::decode(&mut buf)) {
+                            let decoded = match request::decode(&mut buf) {
+                                Ok(decoded) => decoded,
+                                Err(err) => {
+                                    if let Some(err_rsp) = server.on_error(DispatchError::Parse(err)) {
+                                        let ticket = writer.next();
+                                        write_error_rsp(
+                                            &mut *ticket.lock(),
+                                            err_rsp,
+                                            guess_http10(&buf),
+                                            &mut scratch,
+                                        );
+                                    }
+                                    return;
+                                }
+                            };
+                            match decoded {
                                 None => {
+                                    if buf.len() >= config.max_request_size {
+                                        if let Some(err_rsp) =
+                                            server.on_error(DispatchError::PayloadTooLarge)
+                                        {
+                                            let ticket = writer.next();
+                                            write_error_rsp(
+                                                &mut *ticket.lock(),
+                                                err_rsp,
+                                                guess_http10(&buf),
+                                                &mut scratch,
+                                            );
+                                        }
+                                        return;
+                                    }
                                     // need more data
                                     if buf.remaining_mut() < 1024 {
                                         buf.reserve(4096);
                                     }
+                                    let was_idle = buf.is_empty();
                                     let n = {
                                         let read_buf = unsafe { buf.bytes_mut() };
-                                        t!(reader.read(read_buf))
+                                        match reader.read(read_buf) {
+                                            Ok(n) => n,
+                                            Err(ref err) if is_disconnect(err) => return,
+                                            Err(ref err) if is_timeout(err) => {
+                                                if buf.is_empty() {
+                                                    return;
+                                                }
+                                                if let Some(err_rsp) =
+                                                    server.on_error(DispatchError::Timeout)
+                                                {
+                                                    let ticket = writer.next();
+                                                    write_error_rsp(
+                                                        &mut *ticket.lock(),
+                                                        err_rsp,
+                                                        guess_http10(&buf),
+                                                        &mut scratch,
+                                                    );
+                                                }
+                                                return;
+                                            }
+                                            Err(err) => {
+                                                if let Some(err_rsp) =
+                                                    server.on_error(DispatchError::Io(err))
+                                                {
+                                                    let ticket = writer.next();
+                                                    write_error_rsp(
+                                                        &mut *ticket.lock(),
+                                                        err_rsp,
+                                                        guess_http10(&buf),
+                                                        &mut scratch,
+                                                    );
+                                                }
+                                                return;
+                                            }
+                                        }
                                     };
                                     if n == 0 {
                                         //connection was closed
                                         return;
                                     }
+                                    if was_idle {
+                                        // the first byte of a new request just arrived:
+                                        // re-arm client_timeout for the rest of this
+                                        // request's read, in place of keep_alive
+                                        t!(reader.set_read_timeout(Some(config.client_timeout)));
+                                    }
                                     unsafe { buf.advance_mut(n) };
                                 }
                                 Some(req) => {
+                                    req_count += 1;
+                                    let mut close = wants_close(&req);
+                                    if let Some(max) = config.max_requests_per_conn {
+                                        close = close || req_count >= max;
+                                    }
+                                    let http10 = req.version() == 0;
+
                                     let writer = writer.next();
                                     let server = server.clone();
                                     // async process the request
                                     go!(move || {
-                                        let ret =
-                                            server.0.call(req).unwrap_or_else(internal_error_rsp);
+                                        let mut ret = match server.call(req) {
+                                            Ok(rsp) => rsp,
+                                            Err(e) => {
+                                                match server.on_error(DispatchError::Service(e)) {
+                                                    Some(rsp) => rsp,
+                                                    None => return,
+                                                }
+                                            }
+                                        };
+                                        if close {
+                                            ret.header("Connection", "close");
+                                        } else if http10 {
+                                            // HTTP/1.0 defaults to close, so tell a
+                                            // conforming 1.0 client it asked for (and
+                                            // got) a reusable connection
+                                            ret.header("Connection", "keep-alive");
+                                        }
                                         let mut rsp = BytesMut::with_capacity(512);
-                                        response::encode(ret, &mut rsp);
+                                        let tail = response::encode(ret, http10, &mut rsp);
+                                        // lock the sequenced writer for the whole response,
+                                        // including any chunked body, so interleaved
+                                        // responses stay in request order
                                         let mut writer = writer.lock();
-                                        // send the result back to client
                                         t!(writer.write_all(rsp.as_ref()));
+                                        match tail {
+                                            response::Tail::Done => {}
+                                            response::Tail::Chunked(body) => {
+                                                t!(response::write_chunked(body, &mut *writer));
+                                            }
+                                            response::Tail::Upgrade(_) => {
+                                                // the pipeline server hands off requests to
+                                                // per-request coroutines behind a sequenced
+                                                // writer, so there's no single raw stream to
+                                                // upgrade; use `HttpServer` for websockets.
+                                                error!(
+                                                    "websocket upgrade requested on a pipelined \
+                                                     connection; closing it instead"
+                                                );
+                                            }
+                                        }
                                     });
+
+                                    if close {
+                                        return;
+                                    }
+                                    t!(reader.set_read_timeout(Some(config.keep_alive)));
                                 }
                             }
                         }
@@ -1,17 +1,33 @@
+extern crate base64;
 extern crate bytes;
 extern crate httparse;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate may;
+#[cfg(feature = "tls")]
+extern crate rustls;
 extern crate sequencer;
+extern crate sha1;
 extern crate time;
 
+mod acceptor;
+mod client;
 mod date;
+mod error;
 mod request;
 mod response;
 mod http_server;
+mod router;
+mod websocket;
 
+pub use acceptor::{Acceptor, DefaultAcceptor, IoStream};
+#[cfg(feature = "tls")]
+pub use acceptor::TlsAcceptor;
+pub use client::{HttpClient, RequestBuilder};
+pub use error::DispatchError;
 pub use request::Request;
-pub use response::Response;
-pub use http_server::{HttpPipelineServer, HttpServer, HttpService};
+pub use response::{DecodedResponse, Response};
+pub use http_server::{HttpPipelineServer, HttpServer, HttpService, ServerConfig};
+pub use router::{Router, RouterBuilder};
+pub use websocket::UpgradedStream;
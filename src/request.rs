@@ -0,0 +1,197 @@
+//! http request decoding on top of `httparse`
+
+use std::{io, str};
+
+use bytes::{BufMut, BytesMut};
+use httparse;
+
+const MAX_HEADERS: usize = 16;
+
+/// a half-open byte range into the request's underlying buffer
+#[derive(Clone, Copy)]
+struct Slice(usize, usize);
+
+/// a decoded http request
+///
+/// header/method/path/body access is via `Slice`, a byte range into `buf`,
+/// `decode`'s own copy of the request's bytes (not the caller's read buffer)
+pub struct Request {
+    buf: BytesMut,
+    method: Slice,
+    path: Slice,
+    version: u8,
+    headers: Vec<(Slice, Slice)>,
+    body: Slice,
+}
+
+impl Request {
+    pub fn method(&self) -> &str {
+        self.slice(self.method)
+    }
+
+    pub fn path(&self) -> &str {
+        self.slice(self.path)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.buf[self.body.0..self.body.1]
+    }
+
+    /// look up a header by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(k, _)| self.slice(k).eq_ignore_ascii_case(name))
+            .map(|&(_, v)| self.slice(v))
+    }
+
+    fn slice(&self, slice: Slice) -> &str {
+        str::from_utf8(&self.buf[slice.0..slice.1]).unwrap_or("")
+    }
+}
+
+/// serialize an outbound request line, headers and body into `buf`
+///
+/// used by the `client` module; the server side only ever decodes requests,
+/// never encodes them
+pub fn encode(method: &str, path: &str, headers: &[(String, String)], body: &[u8], buf: &mut BytesMut) {
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, path);
+    for (name, value) in headers {
+        head.push_str(name);
+        head.push_str(": ");
+        head.push_str(value);
+        head.push_str("\r\n");
+    }
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    buf.reserve(head.len() + body.len());
+    buf.put_slice(head.as_bytes());
+    buf.put_slice(body);
+}
+
+/// best-effort peek at the request line's HTTP version while a full parse is
+/// still incomplete (`decode` returned `Ok(None)`), for error paths that
+/// need to pick a status line version before the request itself can be
+/// decoded; returns `None` if even the request line hasn't fully arrived
+pub fn peek_version(buf: &[u8]) -> Option<u8> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut r = httparse::Request::new(&mut headers);
+    let _ = r.parse(buf);
+    r.version
+}
+
+/// try to decode one request out of `buf`
+///
+/// returns `Ok(None)` when more data is needed (the request line and headers
+/// haven't fully arrived yet, or they have but the `Content-Length` bytes of
+/// body haven't), `Ok(Some(req))` when a full request - headers and body -
+/// was parsed (and the consumed bytes removed from `buf`), or an `Err` for
+/// malformed input
+pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut r = httparse::Request::new(&mut headers);
+
+    let status = r
+        .parse(buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    let head_len = match status {
+        httparse::Status::Complete(amt) => amt,
+        httparse::Status::Partial => return Ok(None),
+    };
+
+    let content_length = r
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|h| str::from_utf8(h.value).ok())
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let total_len = head_len + content_length;
+    if buf.len() < total_len {
+        // headers are in, but the body hasn't fully arrived yet
+        return Ok(None);
+    }
+
+    let toslice = |a: &[u8]| {
+        let start = a.as_ptr() as usize - buf.as_ptr() as usize;
+        Slice(start, start + a.len())
+    };
+
+    let method = toslice(r.method.unwrap().as_bytes());
+    let path = toslice(r.path.unwrap().as_bytes());
+    let version = r.version.unwrap();
+    let headers = r
+        .headers
+        .iter()
+        .map(|h| (toslice(h.name.as_bytes()), toslice(h.value)))
+        .collect();
+    let body = Slice(head_len, total_len);
+
+    let data = buf.split_to(total_len).freeze();
+    // copy the split-off data into the request's owned buffer
+    let mut owned = BytesMut::with_capacity(data.len());
+    owned.extend_from_slice(&data);
+
+    Ok(Some(Request {
+        buf: owned,
+        method,
+        path,
+        version,
+        headers,
+        body,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trips_method_path_headers_and_body() {
+        let headers = vec![
+            ("Host".to_owned(), "example.com".to_owned()),
+            ("X-Test".to_owned(), "yes".to_owned()),
+        ];
+        let body = b"hello from the client".to_vec();
+
+        let mut buf = BytesMut::new();
+        encode("POST", "/submit", &headers, &body, &mut buf);
+
+        let req = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.path(), "/submit");
+        assert_eq!(req.version(), 1);
+        assert_eq!(req.header("Host"), Some("example.com"));
+        assert_eq!(req.header("x-test"), Some("yes"));
+        assert_eq!(req.body(), body.as_slice());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encode_omits_content_length_for_an_empty_body() {
+        let mut buf = BytesMut::new();
+        encode("GET", "/", &[], b"", &mut buf);
+        let req = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.body(), b"");
+        assert_eq!(req.header("Content-Length"), None);
+    }
+
+    #[test]
+    fn decode_waits_for_the_full_body() {
+        let mut buf = BytesMut::new();
+        encode("POST", "/submit", &[], b"0123456789", &mut buf);
+        // drop the last byte of the body: still incomplete
+        let truncated = buf.len() - 1;
+        buf.truncate(truncated);
+        assert!(decode(&mut buf).unwrap().is_none());
+    }
+}
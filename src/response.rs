@@ -0,0 +1,384 @@
+//! http response encoding and (client-side) decoding
+
+use std::io::{self, Read, Write};
+
+use bytes::{BufMut, BytesMut};
+use httparse;
+
+use date;
+use websocket::UpgradedStream;
+
+/// the body of a `Response`
+enum Body {
+    /// a fully buffered body, sent with a `Content-Length`
+    Fixed(Vec<u8>),
+    /// a body produced incrementally, sent with `Transfer-Encoding: chunked`
+    /// (or buffered on the fly for HTTP/1.0 clients, which don't understand
+    /// chunked encoding)
+    Stream(Box<dyn Read + Send>),
+    /// no body at all: after the response headers go out, the server hands
+    /// the raw connection to this closure instead of looping for another
+    /// request (used for websocket upgrades)
+    Upgrade(Box<dyn FnOnce(UpgradedStream) + Send>),
+}
+
+/// a http response that a `HttpService` hands back to the server
+pub struct Response {
+    status: (u16, &'static str),
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    pub fn new() -> Response {
+        Response {
+            status: (200, "Ok"),
+            headers: Vec::new(),
+            body: Body::Fixed(Vec::new()),
+        }
+    }
+
+    pub fn status_code(&mut self, code: u16, reason: &'static str) -> &mut Self {
+        self.status = (code, reason);
+        self
+    }
+
+    pub fn header(&mut self, name: &'static str, value: &str) -> &mut Self {
+        self.headers.push((name, value.to_owned()));
+        self
+    }
+
+    pub fn body(&mut self, body: &str) -> &mut Self {
+        self.body = Body::Fixed(body.as_bytes().to_vec());
+        self
+    }
+
+    /// reply with a body produced incrementally by `src`, rather than one
+    /// buffered up front
+    ///
+    /// on a HTTP/1.1 connection this is sent with `Transfer-Encoding:
+    /// chunked`; HTTP/1.0 clients don't understand chunked encoding, so
+    /// `encode` falls back to reading `src` to completion and sending it
+    /// with a `Content-Length` instead.
+    pub fn body_stream(mut self, src: impl Read + Send + 'static) -> Self {
+        self.body = Body::Stream(Box::new(src));
+        self
+    }
+
+    /// take over the connection after a websocket handshake
+    ///
+    /// `HttpServer` fills in `Sec-WebSocket-Accept` itself; once the `101
+    /// Switching Protocols` response has been written, `handler` is called
+    /// with the raw connection so it can run RFC 6455 framing on its own.
+    /// `HttpPipelineServer` does neither: it never calls `handler`.
+    pub fn upgrade(handler: impl FnOnce(UpgradedStream) + Send + 'static) -> Response {
+        let mut rsp = Response {
+            status: (101, "Switching Protocols"),
+            headers: Vec::new(),
+            body: Body::Upgrade(Box::new(handler)),
+        };
+        rsp.header("Connection", "Upgrade");
+        rsp.header("Upgrade", "websocket");
+        rsp
+    }
+
+    /// whether this response was built via [`Response::upgrade`]
+    ///
+    /// lets the server decide whether a `Sec-WebSocket-Accept` header is
+    /// appropriate, without the caller needing to track that itself
+    pub fn is_upgrade(&self) -> bool {
+        matches!(self.body, Body::Upgrade(_))
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Response::new()
+    }
+}
+
+fn write_status_and_headers(rsp: &Response, http10: bool, extra: &str, buf: &mut BytesMut) {
+    let mut date = String::new();
+    date::write(&mut date);
+
+    let version = if http10 { "HTTP/1.0" } else { "HTTP/1.1" };
+    let status_line = format!(
+        "{} {} {}\r\nServer: may_minihttp\r\nDate: {}\r\n{}",
+        version, rsp.status.0, rsp.status.1, date, extra
+    );
+    buf.reserve(status_line.len() + 128);
+    buf.put_slice(status_line.as_bytes());
+    for (name, value) in &rsp.headers {
+        buf.put_slice(name.as_bytes());
+        buf.put_slice(b": ");
+        buf.put_slice(value.as_bytes());
+        buf.put_slice(b"\r\n");
+    }
+    buf.put_slice(b"\r\n");
+}
+
+/// what the server loop still has to do after the status line and headers
+/// (and, for a fixed or http/1.0 body, the body itself) have been placed
+/// in `buf` by [`encode`]
+pub enum Tail {
+    /// `buf` already holds the complete response
+    Done,
+    /// pump this source to the client as chunked-encoding, via
+    /// [`write_chunked`]
+    Chunked(Box<dyn Read + Send>),
+    /// the `101 Switching Protocols` response in `buf` is ready to go out;
+    /// once it has been written, hand the connection to this closure
+    Upgrade(Box<dyn FnOnce(UpgradedStream) + Send>),
+}
+
+/// serialize `rsp` into `buf`
+///
+/// `http10` selects the HTTP/1.0 fallback (always buffered, always
+/// `Content-Length`) since those clients cannot consume chunked bodies.
+/// see [`Tail`] for what the caller still needs to do afterwards.
+pub fn encode(rsp: Response, http10: bool, buf: &mut BytesMut) -> Tail {
+    match rsp.body {
+        Body::Fixed(body) => {
+            write_status_and_headers(
+                &rsp,
+                http10,
+                &format!("Content-Length: {}\r\n", body.len()),
+                buf,
+            );
+            buf.reserve(body.len());
+            buf.put_slice(&body);
+            Tail::Done
+        }
+        Body::Stream(mut src) if http10 => {
+            // no chunked support: buffer the whole thing up front
+            let mut body = Vec::new();
+            let _ = src.read_to_end(&mut body);
+            write_status_and_headers(
+                &rsp,
+                http10,
+                &format!("Content-Length: {}\r\n", body.len()),
+                buf,
+            );
+            buf.reserve(body.len());
+            buf.put_slice(&body);
+            Tail::Done
+        }
+        Body::Stream(src) => {
+            write_status_and_headers(&rsp, http10, "Transfer-Encoding: chunked\r\n", buf);
+            Tail::Chunked(src)
+        }
+        Body::Upgrade(handler) => {
+            write_status_and_headers(&rsp, http10, "", buf);
+            Tail::Upgrade(handler)
+        }
+    }
+}
+
+const MAX_HEADERS: usize = 16;
+
+/// a response read back by [`crate::client::HttpClient`]
+pub struct DecodedResponse {
+    status: u16,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl DecodedResponse {
+    pub fn status_code(&self) -> u16 {
+        self.status
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/// try to decode one response out of `buf`, as read off the wire by a client
+///
+/// like `request::decode`, returns `Ok(None)` when more data is needed and
+/// consumes the bytes of exactly one response on success. Understands both
+/// `Content-Length` and `Transfer-Encoding: chunked` bodies.
+pub fn decode(buf: &mut BytesMut) -> io::Result<Option<DecodedResponse>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut r = httparse::Response::new(&mut headers);
+
+    let status = r
+        .parse(buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    let head_len = match status {
+        httparse::Status::Complete(amt) => amt,
+        httparse::Status::Partial => return Ok(None),
+    };
+
+    let code = r.code.unwrap();
+    let reason = r.reason.unwrap().to_owned();
+    let headers: Vec<(String, String)> = r
+        .headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_owned(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect();
+
+    let chunked = headers
+        .iter()
+        .any(|(k, v)| k.eq_ignore_ascii_case("Transfer-Encoding") && v.eq_ignore_ascii_case("chunked"));
+    let content_length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok());
+
+    if chunked {
+        match dechunk(&buf[head_len..]) {
+            Some((body, consumed)) => {
+                buf.split_to(head_len + consumed);
+                Ok(Some(DecodedResponse {
+                    status: code,
+                    reason,
+                    headers,
+                    body,
+                }))
+            }
+            None => Ok(None),
+        }
+    } else {
+        let len = content_length.unwrap_or(0);
+        if buf.len() < head_len + len {
+            return Ok(None);
+        }
+        let body = buf[head_len..head_len + len].to_vec();
+        buf.split_to(head_len + len);
+        Ok(Some(DecodedResponse {
+            status: code,
+            reason,
+            headers,
+            body,
+        }))
+    }
+}
+
+/// parse a chunked-encoding body out of `data`, returning the reassembled
+/// body and the number of bytes consumed (including the terminating chunk),
+/// or `None` if the terminator hasn't arrived yet
+fn dechunk(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let line_end = find_crlf(&data[pos..])? + pos;
+        let size = usize::from_str_radix(
+            std::str::from_utf8(&data[pos..line_end]).ok()?.trim(),
+            16,
+        )
+        .ok()?;
+        let chunk_start = line_end + 2;
+        if size == 0 {
+            // trailing "\r\n" after the zero-length chunk
+            let end = chunk_start + 2;
+            if data.len() < end {
+                return None;
+            }
+            return Some((body, end));
+        }
+        let chunk_end = chunk_start + size;
+        if data.len() < chunk_end + 2 {
+            return None;
+        }
+        body.extend_from_slice(&data[chunk_start..chunk_end]);
+        pos = chunk_end + 2;
+    }
+}
+
+fn find_crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}
+
+/// pump `src` to `writer` as chunked transfer-encoding, ending with the
+/// terminating zero-length chunk
+pub fn write_chunked<W: Write>(mut src: Box<dyn Read + Send>, writer: &mut W) -> io::Result<()> {
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = src.read(&mut chunk)?;
+        if n == 0 {
+            writer.write_all(b"0\r\n\r\n")?;
+            return Ok(());
+        }
+        writer.write_all(format!("{:x}\r\n", n).as_bytes())?;
+        writer.write_all(&chunk[..n])?;
+        writer.write_all(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_chunked_round_trips_through_dechunk() {
+        let body = b"hello, chunked world".to_vec();
+        let mut encoded = Vec::new();
+        write_chunked(Box::new(io::Cursor::new(body.clone())), &mut encoded).unwrap();
+
+        let (decoded, consumed) = dechunk(&encoded).unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn write_chunked_empty_body_is_just_the_terminator() {
+        let mut encoded = Vec::new();
+        write_chunked(Box::new(io::Cursor::new(Vec::new())), &mut encoded).unwrap();
+        assert_eq!(encoded, b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn dechunk_waits_for_more_data() {
+        // a chunk size line with no data behind it yet
+        assert!(dechunk(b"5\r\n").is_none());
+    }
+
+    #[test]
+    fn decode_reads_a_content_length_response() {
+        let mut buf = BytesMut::from(
+            &b"HTTP/1.1 200 Ok\r\nContent-Length: 5\r\nX-Test: yes\r\n\r\nhello"[..],
+        );
+        let rsp = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(rsp.status_code(), 200);
+        assert_eq!(rsp.reason(), "Ok");
+        assert_eq!(rsp.header("X-Test"), Some("yes"));
+        assert_eq!(rsp.body(), b"hello");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_reads_a_chunked_response() {
+        let mut buf = BytesMut::from(
+            &b"HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"[..],
+        );
+        let rsp = decode(&mut buf).unwrap().unwrap();
+        assert_eq!(rsp.body(), b"hello");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_waits_for_a_content_length_body_to_fully_arrive() {
+        let mut buf =
+            BytesMut::from(&b"HTTP/1.1 200 Ok\r\nContent-Length: 5\r\n\r\nhel"[..]);
+        assert!(decode(&mut buf).unwrap().is_none());
+    }
+}
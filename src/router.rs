@@ -0,0 +1,160 @@
+//! a declarative `HttpService` that dispatches to child services by method
+//! and path, instead of a hand-written `match` inside one monolithic `call`
+
+use std::io;
+
+use http_server::HttpService;
+use request::{self, Request};
+use response::Response;
+
+/// a pre-compiled path pattern
+enum Pattern {
+    /// matches the path exactly
+    Exact(String),
+    /// matches any path starting with this prefix, for a route registered
+    /// as e.g. `/static/*`
+    Prefix(String),
+}
+
+impl Pattern {
+    fn compile(pattern: &str) -> Pattern {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => Pattern::Prefix(prefix.to_owned()),
+            None => Pattern::Exact(pattern.to_owned()),
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Pattern::Exact(p) => p == path,
+            Pattern::Prefix(p) => path.starts_with(p.as_str()),
+        }
+    }
+}
+
+struct Route {
+    method: &'static str,
+    pattern: Pattern,
+    service: Box<dyn HttpService + Send + Sync>,
+}
+
+/// composes child services keyed on `(method, path pattern)`; the first
+/// registered route matching a request wins, and anything unmatched falls
+/// through to a default `404 Not Found`
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn builder() -> RouterBuilder {
+        RouterBuilder { routes: Vec::new() }
+    }
+}
+
+impl HttpService for Router {
+    fn call(&self, request: Request) -> io::Result<Response> {
+        for route in &self.routes {
+            if route.method == request.method() && route.pattern.matches(request.path()) {
+                return route.service.call(request);
+            }
+        }
+
+        let mut rsp = Response::new();
+        rsp.status_code(404, "Not Found");
+        rsp.body("Not Found");
+        Ok(rsp)
+    }
+}
+
+/// builds a [`Router`] one route at a time
+pub struct RouterBuilder {
+    routes: Vec<Route>,
+}
+
+impl RouterBuilder {
+    /// register a child service for `method`/`pattern`
+    ///
+    /// `pattern` is matched exactly, unless it ends in `*` (e.g.
+    /// `/static/*`), in which case it matches any path sharing that prefix
+    pub fn route(
+        mut self,
+        method: &'static str,
+        pattern: &str,
+        service: impl HttpService + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::compile(pattern),
+            service: Box::new(service),
+        });
+        self
+    }
+
+    pub fn build(self) -> Router {
+        Router {
+            routes: self.routes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use response;
+
+    struct Reply(&'static str);
+
+    impl HttpService for Reply {
+        fn call(&self, _request: Request) -> io::Result<Response> {
+            let mut rsp = Response::new();
+            rsp.body(self.0);
+            Ok(rsp)
+        }
+    }
+
+    /// dispatch a bare `GET path` request through `router` and return the
+    /// encoded response body, by way of the same decode/encode codecs the
+    /// server uses
+    fn get_body(router: &Router, path: &str) -> Vec<u8> {
+        let mut buf = BytesMut::from(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes());
+        let req = request::decode(&mut buf).unwrap().unwrap();
+        let rsp = router.call(req).unwrap();
+        let mut out = BytesMut::new();
+        response::encode(rsp, true, &mut out);
+        let head_end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        out[head_end..].to_vec()
+    }
+
+    #[test]
+    fn exact_route_matches_method_and_path() {
+        let router = Router::builder()
+            .route("GET", "/hello", Reply("hi"))
+            .build();
+        assert_eq!(get_body(&router, "/hello"), b"hi");
+    }
+
+    #[test]
+    fn wildcard_route_matches_by_prefix() {
+        let router = Router::builder()
+            .route("GET", "/static/*", Reply("asset"))
+            .build();
+        assert_eq!(get_body(&router, "/static/app.js"), b"asset");
+    }
+
+    #[test]
+    fn first_registered_route_wins_on_overlap() {
+        let router = Router::builder()
+            .route("GET", "/static/app.js", Reply("exact"))
+            .route("GET", "/static/*", Reply("wildcard"))
+            .build();
+        assert_eq!(get_body(&router, "/static/app.js"), b"exact");
+    }
+
+    #[test]
+    fn unmatched_request_falls_through_to_404() {
+        let router = Router::builder().build();
+        assert_eq!(get_body(&router, "/missing"), b"Not Found");
+    }
+}
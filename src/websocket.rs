@@ -0,0 +1,106 @@
+//! RFC 6455 handshake helpers
+//!
+//! this module only gets the connection to the point where a handler can
+//! take over; framing the actual websocket messages is left to that
+//! handler, which gets a raw duplex [`UpgradedStream`] to do it on.
+
+use std::io::{self, Read, Write};
+
+use base64;
+use sha1::Sha1;
+
+use acceptor::IoStream;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// true if the `Connection` and `Upgrade` header values describe a websocket
+/// upgrade request: `Connection` lists `Upgrade`, `Upgrade` is `websocket`;
+/// the HTTP/1.1 version check is left to the caller
+pub fn is_upgrade_request(connection: Option<&str>, upgrade: Option<&str>) -> bool {
+    let wants_upgrade = connection
+        .map(|v| v.split(',').any(|tok| tok.trim().eq_ignore_ascii_case("upgrade")))
+        .unwrap_or(false);
+    let is_websocket = upgrade
+        .map(|v| v.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    wants_upgrade && is_websocket
+}
+
+/// compute `Sec-WebSocket-Accept` from the client's `Sec-WebSocket-Key`:
+/// base64(sha1(key ++ GUID))
+pub fn accept_key(client_key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(client_key.as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// the raw, still-open connection handed to a websocket handler after the
+/// `101 Switching Protocols` response has been written
+///
+/// `leftover` is any bytes the server had already buffered past the end of
+/// the upgrade request (e.g. the start of the client's first websocket
+/// frame, read as part of the same `TcpStream::read` as the headers); it is
+/// drained before further bytes are read off the socket.
+pub struct UpgradedStream {
+    stream: Box<dyn IoStream>,
+    leftover: Vec<u8>,
+    pos: usize,
+}
+
+impl UpgradedStream {
+    pub fn new(stream: Box<dyn IoStream>, leftover: Vec<u8>) -> UpgradedStream {
+        UpgradedStream {
+            stream,
+            leftover,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for UpgradedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.leftover.len() {
+            let n = (&self.leftover[self.pos..]).read(buf)?;
+            self.pos += n;
+            return Ok(n);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for UpgradedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_matches_rfc6455_example() {
+        // the worked example from RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn is_upgrade_request_requires_both_headers() {
+        assert!(is_upgrade_request(Some("Upgrade"), Some("websocket")));
+        assert!(is_upgrade_request(
+            Some("keep-alive, Upgrade"),
+            Some("WebSocket")
+        ));
+        assert!(!is_upgrade_request(Some("Upgrade"), None));
+        assert!(!is_upgrade_request(None, Some("websocket")));
+        assert!(!is_upgrade_request(Some("keep-alive"), Some("websocket")));
+    }
+}